@@ -0,0 +1,135 @@
+//! A persistent on-disk cache of the parse, name and scope passes.
+//!
+//! The expensive part of loading a database is parsing it and running the name
+//! and scope passes; for a large database like set.mm this dominates startup.
+//! The results are pure functions of the input bytes and the [`DbOptions`], so
+//! we can record them once and replay them on the next run, exactly as a proving
+//! backend records and replays computed state.
+//!
+//! Each entry is keyed by a 128-bit fingerprint taken over every input file's
+//! contents together with the options. On a hit we deserialize the stored
+//! results and hand them straight to [`Database::init_verify`]; on a miss the
+//! caller runs the passes and writes the results back under the fingerprint.
+//!
+//! Serializing a [`CacheEntry`] requires the parse, name and scope result types
+//! to be serde-serializable, so `SegmentSet` (in `parser`), `Nameset` (in
+//! `name`) and `ScopeResult` (in `scopeck`) each derive `Serialize` and
+//! `Deserialize` at their definitions in `metamath-rs`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+use metamath_rs::database::DbOptions;
+use metamath_rs::name::Nameset;
+use metamath_rs::parser::SegmentSet;
+use metamath_rs::scopeck::ScopeResult;
+use serde::{Deserialize, Serialize};
+
+/// A 128-bit fingerprint of the inputs that produced a cache entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint(u64, u64);
+
+/// The serialized output of the parse, name and scope passes.
+#[derive(Serialize, Deserialize)]
+pub struct CacheEntry {
+    fingerprint: Fingerprint,
+    /// The parse result (`$` segments).
+    pub parse: SegmentSet,
+    /// The name result (symbol and label tables).
+    pub name: Nameset,
+    /// The scope result (frames and hypotheses).
+    pub scope: ScopeResult,
+}
+
+/// Computes the fingerprint of the input files and options.
+///
+/// The two halves are folded with independently seeded hashers so a collision
+/// requires agreement on the full 128 bits.
+#[must_use]
+pub fn fingerprint(files: &[(String, Vec<u8>)], options: &DbOptions) -> Fingerprint {
+    let mut hi = DefaultHasher::new();
+    let mut lo = DefaultHasher::new();
+    0u8.hash(&mut hi);
+    1u8.hash(&mut lo);
+    for (_, bytes) in files {
+        bytes.hash(&mut hi);
+        bytes.hash(&mut lo);
+    }
+    // `DbOptions` is not `Hash`, but its `Debug` form captures every field that
+    // affects the passes.
+    let opts = format!("{options:?}");
+    opts.hash(&mut hi);
+    opts.hash(&mut lo);
+    Fingerprint(hi.finish(), lo.finish())
+}
+
+/// The directory holding cache entries, overridable via `MMKNIFE_CACHE`.
+fn cache_dir() -> PathBuf {
+    std::env::var_os("MMKNIFE_CACHE")
+        .map_or_else(|| PathBuf::from(".mmknife-cache"), PathBuf::from)
+}
+
+/// The path of the entry for a given fingerprint.
+fn entry_path(fp: Fingerprint) -> PathBuf {
+    cache_dir().join(format!("{:016x}{:016x}.ron", fp.0, fp.1))
+}
+
+/// Loads the cached results for `fp`, if a matching entry exists.
+///
+/// A present-but-stale or corrupt file is treated as a miss rather than an
+/// error, so a changed input simply reparses.
+#[must_use]
+pub fn load(fp: Fingerprint) -> Option<CacheEntry> {
+    let data = fs::read_to_string(entry_path(fp)).ok()?;
+    let entry: CacheEntry = ron::from_str(&data).ok()?;
+    (entry.fingerprint == fp).then_some(entry)
+}
+
+/// Writes the results for `fp` back to the cache, ignoring I/O errors (a cache
+/// that cannot be written simply yields no speed-up next time).
+pub fn store(fp: Fingerprint, parse: &SegmentSet, name: &Nameset, scope: &ScopeResult) {
+    let dir = cache_dir();
+    if fs::create_dir_all(&dir).is_err() {
+        return;
+    }
+    let entry = CacheEntry {
+        fingerprint: fp,
+        parse: parse.clone(),
+        name: name.clone(),
+        scope: scope.clone(),
+    };
+    if let Ok(serialized) = ron::to_string(&entry) {
+        let _ = fs::write(entry_path(fp), serialized);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_is_deterministic_and_content_sensitive() {
+        let opts = DbOptions::default();
+        let files = vec![("a.mm".to_owned(), b"$c x $.".to_vec())];
+        assert_eq!(fingerprint(&files, &opts), fingerprint(&files, &opts));
+
+        let changed = vec![("a.mm".to_owned(), b"$c y $.".to_vec())];
+        assert_ne!(fingerprint(&files, &opts), fingerprint(&changed, &opts));
+    }
+
+    #[test]
+    fn fingerprint_round_trips_through_ron() {
+        let fp = Fingerprint(0xdead_beef, 0x0123_4567);
+        let text = ron::to_string(&fp).unwrap();
+        assert_eq!(ron::from_str::<Fingerprint>(&text).unwrap(), fp);
+    }
+
+    #[test]
+    fn entry_path_encodes_both_fingerprint_halves() {
+        let path = entry_path(Fingerprint(0x1, 0x2));
+        let file = path.file_name().unwrap().to_str().unwrap();
+        assert_eq!(file, "00000000000000010000000000000002.ron");
+    }
+}
@@ -0,0 +1,210 @@
+//! Recursive resolution of Metamath `$[ file $]` file-inclusion directives.
+//!
+//! The CLI reads a single file, so a database split across several included
+//! files cannot be loaded. This layer scans the root file (and, recursively,
+//! every file it pulls in) for `$[ filename $]` directives, reads each
+//! referenced file, and returns the flattened `Vec<(String, Vec<u8>)>` the
+//! parser already consumes — the rest of the pipeline is unchanged.
+//!
+//! Following Metamath semantics, a file is included at most once; the layering
+//! mirrors Mercurial's `%include` handling, keeping a single ordered list of
+//! loaded sources with de-duplication. Because a file is never included twice,
+//! an inclusion cycle is simply a file that is already loaded and is skipped —
+//! not an error. Only a genuinely missing file surfaces a diagnostic rather
+//! than panicking.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A diagnostic produced while resolving includes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IncludeError {
+    /// A referenced file could not be found on the search path.
+    NotFound(String),
+}
+
+impl std::fmt::Display for IncludeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IncludeError::NotFound(name) => write!(f, "included file not found: {name}"),
+        }
+    }
+}
+
+/// Resolves all includes reachable from `root`, searching `search_dirs` (in
+/// order) for relative names.
+///
+/// Returns the ordered list of sources keyed by the names the parser will match
+/// `$[ ... $]` directives against, or an [`IncludeError`] for a missing file.
+pub fn resolve(
+    root: &str,
+    search_dirs: &[PathBuf],
+) -> Result<Vec<(String, Vec<u8>)>, IncludeError> {
+    let mut loader = Loader {
+        search_dirs,
+        included: HashSet::new(),
+        out: vec![],
+    };
+    loader.load(root)?;
+    Ok(loader.out)
+}
+
+struct Loader<'a> {
+    search_dirs: &'a [PathBuf],
+    included: HashSet<PathBuf>,
+    out: Vec<(String, Vec<u8>)>,
+}
+
+impl Loader<'_> {
+    /// Locates `name`, returning the first existing match — the name taken as a
+    /// path, then each search directory in order — or `None` when no such file
+    /// exists.
+    fn locate(&self, name: &str) -> Option<PathBuf> {
+        let direct = Path::new(name);
+        if direct.is_file() {
+            return Some(direct.to_path_buf());
+        }
+        self.search_dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|p| p.is_file())
+    }
+
+    /// Reads `name`, appends it to the source list, and recurses into the files
+    /// it includes. A file that has already been loaded is skipped, which also
+    /// resolves cycles: because the file is marked loaded before its own
+    /// includes are followed, a directive that points back to an ancestor simply
+    /// finds it already present and stops.
+    fn load(&mut self, name: &str) -> Result<(), IncludeError> {
+        let path = self
+            .locate(name)
+            .ok_or_else(|| IncludeError::NotFound(name.to_owned()))?;
+        let key = fs::canonicalize(&path).unwrap_or(path);
+
+        if !self.included.insert(key.clone()) {
+            // Already included once: Metamath semantics say include it no more.
+            return Ok(());
+        }
+
+        let bytes = fs::read(&key).map_err(|_| IncludeError::NotFound(name.to_owned()))?;
+        let includes = scan_includes(&bytes);
+        self.out.push((name.to_owned(), bytes));
+
+        for included in includes {
+            self.load(&included)?;
+        }
+        Ok(())
+    }
+}
+
+/// Scans a source buffer for `$[ filename $]` directives, skipping anything
+/// inside `$( ... $)` comments, and returns the referenced file names in order.
+fn scan_includes(bytes: &[u8]) -> Vec<String> {
+    let mut names = vec![];
+    let mut tokens = bytes
+        .split(u8::is_ascii_whitespace)
+        .filter(|t| !t.is_empty());
+    let mut in_comment = false;
+    while let Some(tok) = tokens.next() {
+        if in_comment {
+            if tok == b"$)" {
+                in_comment = false;
+            }
+            continue;
+        }
+        match tok {
+            b"$(" => in_comment = true,
+            b"$[" => {
+                if let Some(name) = tokens.next() {
+                    // Skip the closing `$]`; a malformed directive is left for
+                    // the parser to diagnose.
+                    let _ = tokens.next();
+                    if let Ok(name) = std::str::from_utf8(name) {
+                        names.push(name.to_owned());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Creates a fresh temporary directory unique to this test run.
+    fn temp_dir() -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!(
+            "mmknife-loader-{}-{}",
+            std::process::id(),
+            n
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(dir: &Path, name: &str, contents: &str) {
+        fs::write(dir.join(name), contents).unwrap();
+    }
+
+    fn names(sources: &[(String, Vec<u8>)]) -> Vec<&str> {
+        sources.iter().map(|(n, _)| n.as_str()).collect()
+    }
+
+    #[test]
+    fn diamond_includes_shared_file_once() {
+        let dir = temp_dir();
+        write(&dir, "root.mm", "$[ b.mm $] $[ c.mm $]");
+        write(&dir, "b.mm", "$[ d.mm $]");
+        write(&dir, "c.mm", "$[ d.mm $]");
+        write(&dir, "d.mm", "$c x $.");
+
+        let sources = resolve("root.mm", &[dir.clone()]).unwrap();
+        // `d.mm` is reachable through both `b.mm` and `c.mm` but loaded once.
+        assert_eq!(names(&sources), ["root.mm", "b.mm", "d.mm", "c.mm"]);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn cycle_is_a_silent_skip_not_an_error() {
+        let dir = temp_dir();
+        write(&dir, "a.mm", "$[ b.mm $]");
+        write(&dir, "b.mm", "$[ a.mm $]");
+
+        let sources = resolve("a.mm", &[dir.clone()]).expect("a cycle must not error");
+        assert_eq!(names(&sources), ["a.mm", "b.mm"]);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn missing_file_reports_a_diagnostic() {
+        let dir = temp_dir();
+        write(&dir, "root.mm", "$[ gone.mm $]");
+
+        assert_eq!(
+            resolve("root.mm", &[dir.clone()]),
+            Err(IncludeError::NotFound("gone.mm".to_owned()))
+        );
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn includes_inside_comments_are_ignored() {
+        let dir = temp_dir();
+        write(&dir, "root.mm", "$( $[ skipme.mm $] $) $c x $.");
+
+        let sources = resolve("root.mm", &[dir.clone()]).unwrap();
+        assert_eq!(names(&sources), ["root.mm"]);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
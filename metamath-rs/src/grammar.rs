@@ -0,0 +1,451 @@
+//! An Earley parser that turns math-symbol strings into grammar derivations.
+//!
+//! The [`ProofTree`](crate::proof::ProofTree) docs promise support for "grammar
+//! derivations," but nothing in the tree actually builds them. This module fills
+//! that gap: given the syntax axioms of a database (the `$a` statements defining
+//! the grammar for each typecode), it parses a sequence of math symbols into a
+//! [`ProofTreeArray`] whose trees are the derivation, reusing the existing RPN and
+//! export machinery.
+//!
+//! The recognizer is a textbook Earley parser: one state set per input position,
+//! where each state is a rule together with a dot position and the set index it
+//! originated from. The three operations are PREDICT, SCAN and COMPLETE; after
+//! recognition the completed states are walked backward to reconstruct the parse
+//! trees, with more than one distinct reconstruction of the same span reported as
+//! an ambiguity.
+
+use crate::database::Database;
+use crate::proof::ProofTreeArray;
+use crate::statement::{StatementAddress, StatementType};
+use crate::util::HashMap;
+use crate::verify::ProofBuilder;
+
+/// A single math symbol (constant or variable token).
+pub type Token = Box<[u8]>;
+
+/// A right-hand-side symbol of a syntax rule.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Symbol {
+    /// A constant, matched literally against an input token.
+    Const(Token),
+    /// A variable, standing for any string derivable from the given typecode.
+    Var(Token),
+}
+
+/// A syntax rule derived from one `$a` statement: a typecode (the nonterminal on
+/// the left) produced from a sequence of right-hand-side symbols.
+#[derive(Clone, Debug)]
+pub struct SyntaxRule {
+    /// The `$a` statement defining this production.
+    pub label: StatementAddress,
+    /// The typecode this rule produces.
+    pub typecode: Token,
+    /// The right-hand side, in database order.
+    pub rhs: Vec<Symbol>,
+}
+
+/// A grammar assembled from a database's syntax axioms, indexed by the typecode
+/// each rule produces so PREDICT can look rules up cheaply.
+#[derive(Debug, Default)]
+pub struct Grammar {
+    rules: Vec<SyntaxRule>,
+    by_typecode: HashMap<Token, Vec<usize>>,
+}
+
+/// Reasons an expression could not be parsed into a unique derivation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GrammarError {
+    /// The target typecode has no syntax rules.
+    UnknownTypecode(Token),
+    /// No derivation spans the whole input.
+    ParseError,
+    /// More than one distinct derivation spans the whole input.
+    Ambiguous,
+}
+
+/// An Earley state: a dotted rule plus the set index it was predicted from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Item {
+    rule: usize,
+    dot: usize,
+    origin: usize,
+}
+
+impl Grammar {
+    /// Builds a grammar from the syntax axioms of `db`.
+    ///
+    /// Every `$a` statement `tc s1 … sn` becomes a rule producing typecode `tc`
+    /// from the right-hand side `s1 … sn`. A right-hand-side token that a `$f`
+    /// statement has typed is a nonterminal standing for the typecode it may be
+    /// replaced by; every other token is a constant matched literally. This is
+    /// the entry point for parsing expressions of a real database, as opposed to
+    /// the hand-built rules assembled with [`add_rule`](Self::add_rule).
+    #[must_use]
+    pub fn from_database(db: &Database) -> Self {
+        // `$f typecode var` assigns each variable the typecode it derives; a
+        // right-hand-side variable is mapped to that typecode below.
+        let mut var_type: HashMap<Token, Token> = HashMap::default();
+        for stmt in db.statements() {
+            if stmt.statement_type() == StatementType::Floating {
+                let mut math = stmt.math_iter();
+                if let (Some(tc), Some(var)) = (math.next(), math.next()) {
+                    var_type.insert(var.slice.into(), tc.slice.into());
+                }
+            }
+        }
+
+        let mut grammar = Grammar::default();
+        for stmt in db.statements() {
+            if stmt.statement_type() != StatementType::Axiom {
+                continue;
+            }
+            let mut math = stmt.math_iter();
+            let Some(typecode) = math.next() else { continue };
+            let rhs = math
+                .map(|sym| {
+                    let tok: Token = sym.slice.into();
+                    match var_type.get(&tok) {
+                        Some(nt) => Symbol::Var(nt.clone()),
+                        None => Symbol::Const(tok),
+                    }
+                })
+                .collect();
+            grammar.add_rule(SyntaxRule {
+                label: stmt.address(),
+                typecode: typecode.slice.into(),
+                rhs,
+            });
+        }
+        grammar
+    }
+
+    /// Adds a syntax rule to the grammar.
+    pub fn add_rule(&mut self, rule: SyntaxRule) {
+        let ix = self.rules.len();
+        self.by_typecode
+            .entry(rule.typecode.clone())
+            .or_default()
+            .push(ix);
+        self.rules.push(rule);
+    }
+
+    /// The next right-hand-side symbol after an item's dot, or `None` when the
+    /// dot has reached the end of the rule (the item is complete).
+    fn next_symbol(&self, item: &Item) -> Option<&Symbol> {
+        self.rules[item.rule].rhs.get(item.dot)
+    }
+
+    /// Parses `input` as a derivation of `typecode`, returning the derivation as
+    /// a [`ProofTreeArray`].
+    ///
+    /// Recognition builds the Earley state sets; reconstruction then walks the
+    /// completed states backward, feeding each production to [`ProofBuilder`] so
+    /// the resulting trees are deduplicated exactly like verified proofs.
+    pub fn parse(&self, typecode: &[u8], input: &[Token]) -> Result<ProofTreeArray, GrammarError> {
+        let seeds = self
+            .by_typecode
+            .get(typecode)
+            .ok_or_else(|| GrammarError::UnknownTypecode(typecode.into()))?;
+
+        // One state set per input position, plus one past the end.
+        let mut sets: Vec<Vec<Item>> = vec![vec![]; input.len() + 1];
+        for &rule in seeds {
+            sets[0].push(Item {
+                rule,
+                dot: 0,
+                origin: 0,
+            });
+        }
+
+        for i in 0..sets.len() {
+            // The set grows as PREDICT and COMPLETE add states; index by position
+            // so newly pushed states are processed in turn.
+            let mut j = 0;
+            while j < sets[i].len() {
+                let item = sets[i][j];
+                match self.next_symbol(&item) {
+                    Some(Symbol::Var(nt)) => self.predict(&mut sets[i], i, nt),
+                    Some(Symbol::Const(tok)) => {
+                        if input.get(i) == Some(tok) {
+                            let advanced = Item {
+                                dot: item.dot + 1,
+                                ..item
+                            };
+                            push_unique(&mut sets[i + 1], advanced);
+                        }
+                    }
+                    None => self.complete(&mut sets, i, item),
+                }
+                j += 1;
+            }
+        }
+
+        self.reconstruct(typecode, input, &sets)
+    }
+
+    /// PREDICT: for a nonterminal right after the dot, add every rule for that
+    /// typecode at the current position with its dot at zero.
+    fn predict(&self, set: &mut Vec<Item>, at: usize, nonterminal: &Token) {
+        if let Some(rules) = self.by_typecode.get(nonterminal) {
+            for &rule in rules {
+                push_unique(
+                    set,
+                    Item {
+                        rule,
+                        dot: 0,
+                        origin: at,
+                    },
+                );
+            }
+        }
+    }
+
+    /// COMPLETE: an item whose dot reached the end advances every waiting state
+    /// in its origin set that was looking for this rule's typecode.
+    fn complete(&self, sets: &mut [Vec<Item>], i: usize, item: Item) {
+        let typecode = &self.rules[item.rule].typecode;
+        let waiting: Vec<Item> = sets[item.origin]
+            .iter()
+            .filter(|w| matches!(self.next_symbol(w), Some(Symbol::Var(nt)) if nt == typecode))
+            .copied()
+            .collect();
+        for w in waiting {
+            let advanced = Item {
+                dot: w.dot + 1,
+                ..w
+            };
+            push_unique(&mut sets[i], advanced);
+        }
+    }
+
+    /// Walks the completed states backward to build the derivation trees.
+    ///
+    /// Only rules that actually completed over the whole span in the Earley sets
+    /// can start a parse, and reconstruction is driven entirely by the sets (see
+    /// [`Self::derive`]), so the recognizer's work is reused rather than thrown
+    /// away. A span with more than one distinct reconstruction is ambiguous.
+    fn reconstruct(
+        &self,
+        typecode: &[u8],
+        input: &[Token],
+        sets: &[Vec<Item>],
+    ) -> Result<ProofTreeArray, GrammarError> {
+        let mut arr = ProofTreeArray::new(false);
+        let mut memo = HashMap::default();
+        let mut derivations: Vec<usize> = vec![];
+        for &rule in self.by_typecode.get(typecode).into_iter().flatten() {
+            if !self.completed(rule, 0, input.len(), sets) {
+                continue;
+            }
+            for tree in self.derive(rule, 0, input.len(), input, sets, &mut arr, &mut memo) {
+                if !derivations.contains(&tree) {
+                    derivations.push(tree);
+                }
+            }
+        }
+        match derivations.as_slice() {
+            [] => Err(GrammarError::ParseError),
+            [only] => {
+                arr.qed = *only;
+                Ok(arr)
+            }
+            _ => Err(GrammarError::Ambiguous),
+        }
+    }
+
+    /// Whether `rule` has a completed Earley item spanning `input[start..end]`,
+    /// i.e. an item in set `end` whose dot has reached the end of the rule and
+    /// whose origin is `start`.
+    fn completed(&self, rule: usize, start: usize, end: usize, sets: &[Vec<Item>]) -> bool {
+        let dot = self.rules[rule].rhs.len();
+        sets[end]
+            .iter()
+            .any(|it| it.rule == rule && it.dot == dot && it.origin == start)
+    }
+
+    /// The completions of nonterminal `nt` starting at `pos`: every `(rule, q)`
+    /// such that some rule producing `nt` has a completed item in set `q` with
+    /// origin `pos`. Reading these straight off the Earley sets restricts the
+    /// reconstruction to splits the recognizer actually found, instead of trying
+    /// every `pos..=end`.
+    fn completions(&self, nt: &Token, pos: usize, end: usize, sets: &[Vec<Item>]) -> Vec<(usize, usize)> {
+        let mut out = vec![];
+        for (q, set) in sets.iter().enumerate().take(end + 1).skip(pos) {
+            for it in set {
+                if it.origin == pos
+                    && it.dot == self.rules[it.rule].rhs.len()
+                    && &self.rules[it.rule].typecode == nt
+                {
+                    out.push((it.rule, q));
+                }
+            }
+        }
+        out
+    }
+
+    /// Produces the distinct tree indices derivable by `rule` over
+    /// `input[start..end]`, reconstructed from the Earley sets.
+    ///
+    /// Results are memoized per `(rule, start, end)`. A placeholder empty result
+    /// is recorded before recursing, so a unit- or left-recursive production that
+    /// re-enters the same span (e.g. `A → B`, `B → A`) terminates with no
+    /// spurious derivation rather than overflowing the stack. Because
+    /// [`ProofBuilder::build`] deduplicates, two spellings of the same tree yield
+    /// one index; more than one distinct index is genuine ambiguity.
+    fn derive(
+        &self,
+        rule: usize,
+        start: usize,
+        end: usize,
+        input: &[Token],
+        sets: &[Vec<Item>],
+        arr: &mut ProofTreeArray,
+        memo: &mut HashMap<(usize, usize, usize), Vec<usize>>,
+    ) -> Vec<usize> {
+        if let Some(cached) = memo.get(&(rule, start, end)) {
+            return cached.clone();
+        }
+        // Break cyclic re-entry for this span before descending into children.
+        memo.insert((rule, start, end), vec![]);
+
+        let rhs = &self.rules[rule].rhs;
+        let mut frontier: Vec<(usize, Vec<usize>)> = vec![(start, vec![])];
+        for sym in rhs {
+            let mut next = vec![];
+            for (pos, children) in &frontier {
+                match sym {
+                    Symbol::Const(tok) => {
+                        if input.get(*pos) == Some(tok) {
+                            next.push((pos + 1, children.clone()));
+                        }
+                    }
+                    Symbol::Var(nt) => {
+                        for (sub, q) in self.completions(nt, *pos, end, sets) {
+                            for tree in self.derive(sub, *pos, q, input, sets, arr, memo) {
+                                let mut c = children.clone();
+                                c.push(tree);
+                                next.push((q, c));
+                            }
+                        }
+                    }
+                }
+            }
+            frontier = next;
+        }
+
+        let mut trees: Vec<usize> = vec![];
+        for (_, children) in frontier.into_iter().filter(|(pos, _)| *pos == end) {
+            let mut hyps = vec![];
+            for child in children {
+                arr.push(&mut hyps, child);
+            }
+            let tree = arr.build(self.rules[rule].label, hyps, &[], 0..0);
+            if !trees.contains(&tree) {
+                trees.push(tree);
+            }
+        }
+        memo.insert((rule, start, end), trees.clone());
+        trees
+    }
+}
+
+/// Pushes an Earley item only if an equal one is not already present, keeping
+/// each state set free of duplicates so the worklist terminates.
+fn push_unique(set: &mut Vec<Item>, item: Item) {
+    if !set.iter().any(|e| e == &item) {
+        set.push(item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statement::{SegmentId, StatementAddress};
+
+    fn addr(n: u32) -> StatementAddress {
+        StatementAddress::new(SegmentId(n), 0)
+    }
+
+    fn tok(s: &str) -> Token {
+        s.as_bytes().into()
+    }
+
+    fn input(tokens: &[&str]) -> Vec<Token> {
+        tokens.iter().copied().map(tok).collect()
+    }
+
+    /// `S → S + S | a`, the textbook ambiguous expression grammar.
+    fn expr_grammar() -> Grammar {
+        let mut g = Grammar::default();
+        g.add_rule(SyntaxRule {
+            label: addr(1),
+            typecode: tok("S"),
+            rhs: vec![Symbol::Var(tok("S")), Symbol::Const(tok("+")), Symbol::Var(tok("S"))],
+        });
+        g.add_rule(SyntaxRule {
+            label: addr(2),
+            typecode: tok("S"),
+            rhs: vec![Symbol::Const(tok("a"))],
+        });
+        g
+    }
+
+    #[test]
+    fn unknown_typecode() {
+        let g = expr_grammar();
+        assert_eq!(
+            g.parse(b"T", &input(&["a"])),
+            Err(GrammarError::UnknownTypecode(tok("T")))
+        );
+    }
+
+    #[test]
+    fn no_derivation_is_a_parse_error() {
+        let g = expr_grammar();
+        assert_eq!(g.parse(b"S", &input(&["a", "+"])), Err(GrammarError::ParseError));
+    }
+
+    #[test]
+    fn unambiguous_parse_yields_a_single_derivation() {
+        let g = expr_grammar();
+        let arr = g.parse(b"S", &input(&["a"])).expect("should parse");
+        // One leaf derivation, the `a` production.
+        assert_eq!(arr.trees.len(), 1);
+        assert_eq!(arr.trees[arr.qed].address, addr(2));
+    }
+
+    #[test]
+    fn ambiguous_parse_is_reported() {
+        let g = expr_grammar();
+        // `a + a + a` has both left- and right-associated derivations.
+        assert_eq!(
+            g.parse(b"S", &input(&["a", "+", "a", "+", "a"])),
+            Err(GrammarError::Ambiguous)
+        );
+    }
+
+    #[test]
+    fn unit_cycle_terminates() {
+        // `A → B`, `B → A`, `A → a`: the unit cycle must not overflow the stack.
+        let mut g = Grammar::default();
+        g.add_rule(SyntaxRule {
+            label: addr(1),
+            typecode: tok("A"),
+            rhs: vec![Symbol::Var(tok("B"))],
+        });
+        g.add_rule(SyntaxRule {
+            label: addr(2),
+            typecode: tok("B"),
+            rhs: vec![Symbol::Var(tok("A"))],
+        });
+        g.add_rule(SyntaxRule {
+            label: addr(3),
+            typecode: tok("A"),
+            rhs: vec![Symbol::Const(tok("a"))],
+        });
+        // The direct `A → a` and the cyclic `A → B → A → a` are distinct finite
+        // derivations of the same span, so the call returns (without overflowing)
+        // and reports the ambiguity.
+        assert_eq!(g.parse(b"A", &input(&["a"])), Err(GrammarError::Ambiguous));
+    }
+}
@@ -0,0 +1,7 @@
+//! A library for manipulating [Metamath](http://us.metamath.org/#faq)
+//! databases. The entry point for all API operations is in the `database`
+//! module, as is a discussion of the data representation.
+
+pub mod grammar;
+pub mod proof;
+mod util;
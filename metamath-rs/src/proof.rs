@@ -9,6 +9,18 @@ use std::collections::BinaryHeap;
 use std::hash::{Hash, Hasher};
 use std::ops::Range;
 
+/// A 128-bit fingerprint identifying a proof tree up to structural equality.
+///
+/// A single `u64` hash is not enough to key the dedup map: two structurally
+/// distinct trees that happen to collide would be silently merged in
+/// [`ProofBuilder::build`], corrupting the proof with no verification error.
+/// Keying on a 128-bit value (stored as two independent halves, as rustc's
+/// `rustc_data_structures::fingerprint::Fingerprint` does) makes a collision
+/// negligibly unlikely, and the dedup map still confirms identity with a
+/// shallow [`PartialEq`] on any hit.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Fingerprint(u64, u64);
+
 /// A tree structure for storing proofs and grammar derivations.
 #[derive(Clone, Debug, Eq)]
 pub struct ProofTree {
@@ -16,8 +28,8 @@ pub struct ProofTree {
     pub address: StatementAddress,
     /// The hypotheses ($e and $f) in database order, indexes into the parent `ProofTreeArray`.
     pub children: Vec<usize>,
-    /// The precomputed hash for this tree.
-    hash: u64,
+    /// The precomputed 128-bit fingerprint for this tree.
+    fingerprint: Fingerprint,
 }
 
 impl PartialEq for ProofTree {
@@ -32,7 +44,7 @@ impl Hash for ProofTree {
     where
         H: Hasher,
     {
-        self.hash.hash(state)
+        self.fingerprint.hash(state)
     }
 }
 
@@ -40,15 +52,25 @@ impl ProofTree {
     /// Create a new proof tree using the given atom and children.
     #[must_use]
     pub fn new(parent: &ProofTreeArray, address: StatementAddress, children: Vec<usize>) -> Self {
-        let mut hasher = DefaultHasher::new();
-        address.hash(&mut hasher);
+        // Compute the two halves with independently seeded hashers so that a
+        // collision requires agreement on the full 128 bits.
+        let mut hi = DefaultHasher::new();
+        let mut lo = DefaultHasher::new();
+        0u8.hash(&mut hi);
+        1u8.hash(&mut lo);
+        address.hash(&mut hi);
+        address.hash(&mut lo);
         for &ix in &children {
-            parent.trees[ix].hash(&mut hasher);
+            let child = parent.trees[ix].fingerprint;
+            child.0.hash(&mut hi);
+            child.1.hash(&mut hi);
+            child.0.hash(&mut lo);
+            child.1.hash(&mut lo);
         }
         ProofTree {
             address,
             children,
-            hash: hasher.finish(),
+            fingerprint: Fingerprint(hi.finish(), lo.finish()),
         }
     }
 }
@@ -57,7 +79,7 @@ impl ProofTree {
 /// in proof order
 #[derive(Debug, Clone)]
 pub struct ProofTreeArray {
-    map: HashMap<u64, usize>,
+    map: HashMap<Fingerprint, usize>,
     /// The list of proof trees
     pub trees: Vec<ProofTree>,
     /// The uncompressed strings for each proof tree.
@@ -120,10 +142,17 @@ impl ProofTreeArray {
         }
     }
 
-    /// Get the index of a proof tree in the array
+    /// Get the index of a proof tree in the array.
+    ///
+    /// On a fingerprint hit the candidate is still confirmed with the shallow
+    /// [`PartialEq`] (`address` + `children`), so a 128-bit collision between
+    /// distinct trees reports a miss rather than silently reusing the index.
     #[must_use]
     pub fn index(&self, tree: &ProofTree) -> Option<usize> {
-        self.map.get(&tree.hash).copied()
+        self.map
+            .get(&tree.fingerprint)
+            .copied()
+            .filter(|&ix| self.trees[ix] == *tree)
     }
 
 
@@ -266,6 +295,114 @@ impl ProofTreeArray {
         env.out
     }
 
+    /// Write the proof as an RPN sequence with a backreference set chosen to
+    /// minimize the total number of emitted tokens.
+    ///
+    /// Each normal step costs one label token and each backreference one token;
+    /// forward-reference markers are not counted. Under that measure, sharing a
+    /// subtree that has children is strictly cheaper whenever it is emitted more
+    /// than once, and sharing a leaf never is. [`to_rpn`](Self::to_rpn) decides
+    /// from the raw parent count, which also counts parents that are not
+    /// reachable from `qed`; this method decides from the number of times each
+    /// step is actually emitted, so its output differs from `to_rpn`'s only where
+    /// such an unreachable parent exists. The result has the same shape as
+    /// `to_rpn`'s output; see also [`to_rpn_styled`](Self::to_rpn_styled).
+    #[must_use]
+    pub fn to_rpn_minimized(&self, explicit: bool) -> Vec<RPNStep> {
+        // The multiplicity that matters for a node is not its raw in-degree but
+        // how many times it is actually emitted: when an ancestor is itself
+        // hoisted to a backreference, the nodes beneath it are emitted fewer
+        // times. `emit[v]` is that count, propagated top-down from the `qed` root.
+        //
+        // This needs a single pass, not a fixed point. Sharing a reused interior
+        // node never raises any other node's cost — it emits its own token the
+        // same number of times (once as a normal step plus one backref per extra
+        // use, versus once per emission) while strictly lowering its
+        // descendants' emission counts — so the optimum hoists exactly the
+        // interior nodes that are emitted more than once. Children are pushed
+        // before their parents, so descending index order visits each node only
+        // after every parent that can reach it; `emit[v]` is therefore final by
+        // the time `v` is decided, and each node is decided once.
+        let n = self.trees.len();
+        let mut shared = vec![false; n];
+        let mut emit = vec![0usize; n];
+        emit[self.qed] = 1;
+        for v in (0..n).rev() {
+            // A leaf or a singly-emitted node is always cheaper inline.
+            shared[v] = emit[v] > 1 && !self.trees[v].children.is_empty();
+            let body = if shared[v] { 1 } else { emit[v] };
+            for &c in &self.trees[v].children {
+                emit[c] += body;
+            }
+        }
+
+        struct Env<'a> {
+            arr: &'a ProofTreeArray,
+            explicit: bool,
+            shared: Vec<bool>,
+            out: Vec<RPNStep>,
+            backrefs: Vec<usize>,
+            count: usize,
+        }
+
+        fn output_step(env: &mut Env<'_>, step: usize, hyp: Option<(StatementAddress, usize)>) {
+            let step = if env.backrefs[step] == 0 {
+                let tree = &env.arr.trees[step];
+                for (i, &hix) in tree.children.iter().enumerate() {
+                    let n_hyp = if env.explicit {
+                        Some((tree.address, i))
+                    } else {
+                        None
+                    };
+                    output_step(env, hix, n_hyp);
+                }
+                RPNStep::Normal {
+                    fwdref: if env.shared[step] {
+                        env.count += 1;
+                        env.backrefs[step] = env.count;
+                        env.count
+                    } else {
+                        0
+                    },
+                    addr: tree.address,
+                    hyp,
+                }
+            } else {
+                RPNStep::Backref {
+                    backref: env.backrefs[step],
+                    hyp,
+                }
+            };
+            env.out.push(step);
+        }
+        let mut env = Env {
+            arr: self,
+            explicit,
+            shared,
+            out: vec![],
+            backrefs: vec![0; self.trees.len()],
+            count: 0,
+        };
+        output_step(&mut env, self.qed, None);
+        env.out
+    }
+
+    /// Write the proof as an RPN sequence for the given [`ProofStyle`].
+    ///
+    /// Packed styles (`/compressed`, `/packed` and `/packed/explicit`) use the
+    /// backreference set that [`to_rpn_minimized`](Self::to_rpn_minimized)
+    /// chooses to minimize emitted length; unpacked styles never share, so every
+    /// step is written out in full as [`normal_iter`](Self::normal_iter) yields
+    /// it, with no forward references or backreferences. `_parents` is not read.
+    #[must_use]
+    pub fn to_rpn_styled(&self, style: ProofStyle, _parents: &[usize]) -> Vec<RPNStep> {
+        if style.packed() {
+            self.to_rpn_minimized(style.explicit())
+        } else {
+            self.normal_iter(style.explicit()).collect()
+        }
+    }
+
     /// Produce an iterator over the steps in the proof in
     /// normal/uncompressed mode. (Because this can potentially
     /// be *very* long, we do not store the list and just stream it.)
@@ -347,7 +484,7 @@ impl ProofBuilder for ProofTreeArray {
         let tree = ProofTree::new(self, addr, trees);
         self.index(&tree).unwrap_or_else(|| {
             let ix = self.trees.len();
-            self.map.insert(tree.hash, ix);
+            self.map.insert(tree.fingerprint, ix);
             self.trees.push(tree);
             if let Some(exprs) = &mut self.exprs {
                 let mut u_expr = vec![b' '];
@@ -382,6 +519,82 @@ pub enum ProofStyle {
     PackedExplicit,
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statement::{SegmentId, StatementAddress};
+
+    fn addr(n: u32) -> StatementAddress {
+        StatementAddress::new(SegmentId(n), 0)
+    }
+
+    fn fwdrefs(steps: &[RPNStep]) -> usize {
+        steps
+            .iter()
+            .filter(|s| matches!(s, RPNStep::Normal { fwdref, .. } if *fwdref > 0))
+            .count()
+    }
+
+    fn backrefs(steps: &[RPNStep]) -> usize {
+        steps
+            .iter()
+            .filter(|s| matches!(s, RPNStep::Backref { .. }))
+            .count()
+    }
+
+    #[test]
+    fn sharing_a_reused_subtree_is_worthwhile() {
+        // `root` applies two parents that both reference the same interior `s`,
+        // so `s` is genuinely emitted twice and should be hoisted.
+        let mut arr = ProofTreeArray::new(false);
+        let a = arr.build(addr(1), vec![], &[], 0..0);
+        let s = arr.build(addr(2), vec![a], &[], 0..0);
+        let p1 = arr.build(addr(3), vec![s], &[], 0..0);
+        let p2 = arr.build(addr(4), vec![s], &[], 0..0);
+        arr.qed = arr.build(addr(5), vec![p1, p2], &[], 0..0);
+
+        let parents = arr.count_parents();
+        let min = arr.to_rpn_minimized(false);
+        // `s` is emitted once and referenced once by a backreference.
+        assert_eq!(backrefs(&min), 1);
+        // Never longer than the greedy output for a genuinely shared subtree.
+        assert!(min.len() <= arr.to_rpn(&parents, false).len());
+    }
+
+    #[test]
+    fn unreachable_parent_does_not_force_a_backreference() {
+        // `dead` references `s` but is not reachable from `qed`, so `s` is really
+        // emitted only once. The static in-degree counts `dead`, so the greedy
+        // `to_rpn` marks `s` reusable and wastes a forward reference; the
+        // emission-count DP sees through it and emits no backreference marker.
+        let mut arr = ProofTreeArray::new(false);
+        let a = arr.build(addr(1), vec![], &[], 0..0);
+        let s = arr.build(addr(2), vec![a], &[], 0..0);
+        let live = arr.build(addr(3), vec![s], &[], 0..0);
+        let _dead = arr.build(addr(4), vec![s], &[], 0..0);
+        arr.qed = arr.build(addr(5), vec![live], &[], 0..0);
+
+        let parents = arr.count_parents();
+        assert_eq!(parents[s], 2);
+        assert_eq!(fwdrefs(&arr.to_rpn(&parents, false)), 1);
+        assert_eq!(fwdrefs(&arr.to_rpn_minimized(false)), 0);
+    }
+
+    #[test]
+    fn styled_output_only_packs_when_packed() {
+        let mut arr = ProofTreeArray::new(false);
+        let a = arr.build(addr(1), vec![], &[], 0..0);
+        let s = arr.build(addr(2), vec![a], &[], 0..0);
+        let p1 = arr.build(addr(3), vec![s], &[], 0..0);
+        let p2 = arr.build(addr(4), vec![s], &[], 0..0);
+        arr.qed = arr.build(addr(5), vec![p1, p2], &[], 0..0);
+        let parents = arr.count_parents();
+
+        assert!(backrefs(&arr.to_rpn_styled(ProofStyle::Packed, &parents)) > 0);
+        assert_eq!(backrefs(&arr.to_rpn_styled(ProofStyle::Normal, &parents)), 0);
+    }
+}
+
 impl ProofStyle {
     /// Returns `true` if this is in explicit style (showing proof hypotheses labels
     /// on each step)